@@ -125,32 +125,353 @@
 
 use proc_macro::TokenStream;
 use quote::{quote, quote_spanned};
+use syn::ext::IdentExt;
 use syn::spanned::Spanned;
 use syn::ExprIndex;
 use syn::{parse_macro_input, visit_mut::VisitMut, Expr};
 
+/// Configuration parsed from the arguments of `#[traceback(...)]`.
+///
+/// Arguments follow the usual `name = value` attribute style, for example
+/// `#[traceback(index = false, try = true, context = "parsing config")]`. Each
+/// recognized key toggles or parameterizes one of the rewrites performed by
+/// [`TracingVisitor`]; an unrecognized key produces a compile error pointing at
+/// the offending token.
+struct TracebackConfig {
+    /// Whether to rewrite `Expr::Index` into a safe `.get(i)`. Enabled by
+    /// default; set `index = false` for functions where panic-on-OOB is wanted.
+    index: bool,
+    /// Whether to rewrite `?` into a traceback-capturing `match`. Enabled by
+    /// default.
+    try_: bool,
+    /// A static label threaded into every generated `traceback!` call.
+    context: Option<String>,
+    /// When set, the `?` rewrite emits a `tracing::event!` at this level just
+    /// before wrapping the propagated error. Holds the `tracing::Level` variant
+    /// identifier (e.g. `WARN`). Off by default, so no `tracing` dependency is
+    /// required unless the option is used.
+    log: Option<syn::Ident>,
+    /// When set, the `?` rewrite pushes a `{file, line, col, note}` frame onto
+    /// the error's trace history (via the companion crate's `push_frame` API)
+    /// instead of re-wrapping the error opaquely. Written as a bare
+    /// `#[traceback(history)]`.
+    history: bool,
+    /// When set, every `let` binding (and simple assignment) records the bound
+    /// identifier and its `{:?}` value into the current traceback context, so a
+    /// later error in the same function carries a snapshot of its locals.
+    /// Written as a bare `#[traceback(trace_vars)]`.
+    trace_vars: bool,
+}
+
+impl Default for TracebackConfig {
+    fn default() -> Self {
+        TracebackConfig {
+            index: true,
+            try_: true,
+            context: None,
+            log: None,
+            history: false,
+            trace_vars: false,
+        }
+    }
+}
+
+impl syn::parse::Parse for TracebackConfig {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mut config = TracebackConfig::default();
+        while !input.is_empty() {
+            // `try` is a keyword, so parse the key with `parse_any`.
+            let key = syn::Ident::parse_any(input)?;
+            // Bare flags (e.g. `history`) may appear without a `= value`.
+            let has_value = input.peek(syn::Token![=]);
+            if has_value {
+                input.parse::<syn::Token![=]>()?;
+            }
+            match key.to_string().as_str() {
+                "index" => config.index = input.parse::<syn::LitBool>()?.value,
+                "try" => config.try_ = input.parse::<syn::LitBool>()?.value,
+                "history" => {
+                    config.history = if has_value {
+                        input.parse::<syn::LitBool>()?.value
+                    } else {
+                        true
+                    };
+                }
+                "trace_vars" => {
+                    config.trace_vars = if has_value {
+                        input.parse::<syn::LitBool>()?.value
+                    } else {
+                        true
+                    };
+                }
+                "context" => config.context = Some(input.parse::<syn::LitStr>()?.value()),
+                "log" => {
+                    let lit = input.parse::<syn::LitStr>()?;
+                    let variant = match lit.value().as_str() {
+                        "trace" => "TRACE",
+                        "debug" => "DEBUG",
+                        "info" => "INFO",
+                        "warn" => "WARN",
+                        "error" => "ERROR",
+                        other => {
+                            return Err(syn::Error::new(
+                                lit.span(),
+                                format!("unknown `log` level `{other}`, expected one of trace/debug/info/warn/error"),
+                            ))
+                        }
+                    };
+                    config.log = Some(syn::Ident::new(variant, lit.span()));
+                }
+                _ => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        format!("unknown `traceback` option `{key}`"),
+                    ))
+                }
+            }
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<syn::Token![,]>()?;
+        }
+        Ok(config)
+    }
+}
+
 #[proc_macro_attribute]
-pub fn traceback(_attrs: TokenStream, input: TokenStream) -> TokenStream {
+pub fn traceback(attrs: TokenStream, input: TokenStream) -> TokenStream {
+    let config = parse_macro_input!(attrs as TracebackConfig);
     let mut function = parse_macro_input!(input as syn::ItemFn);
 
-    let mut visitor = TracingVisitor;
+    // `history` and `trace_vars` depend on companion-crate APIs that only exist
+    // when this crate is built with the matching feature. Rather than silently
+    // degrading to a no-op, refuse with a clear diagnostic when the feature is
+    // not compiled in, so the requested behavior can never vanish unannounced.
+    if config.history && !cfg!(feature = "history") {
+        return syn::Error::new_spanned(
+            &function.sig.ident,
+            "`#[traceback(history)]` requires the `history` feature of `traceback-derive` \
+             (paired with a `traceback-error` providing `push_frame`)",
+        )
+        .to_compile_error()
+        .into();
+    }
+    if config.trace_vars && !cfg!(feature = "trace_vars") {
+        return syn::Error::new_spanned(
+            &function.sig.ident,
+            "`#[traceback(trace_vars)]` requires the `trace_vars` feature of `traceback-derive` \
+             (paired with a `traceback-error` providing `record_var`)",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let returns_result = signature_returns_result(&function.sig.output);
+
+    let mut visitor = TracingVisitor {
+        config,
+        returns_result,
+        in_nested_item: false,
+    };
     visitor.visit_item_fn_mut(&mut function);
 
     TokenStream::from(quote! { #function })
 }
 
-struct TracingVisitor;
+/// Returns `true` when the function signature resolves to a `Result<..>`, which
+/// is a prerequisite for the `unwrap`/`expect` rewrite to produce an early
+/// `return Err(..)`.
+fn signature_returns_result(output: &syn::ReturnType) -> bool {
+    let ty = match output {
+        syn::ReturnType::Type(_, ty) => ty,
+        syn::ReturnType::Default => return false,
+    };
+    match &**ty {
+        syn::Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .map(|seg| seg.ident == "Result")
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+struct TracingVisitor {
+    config: TracebackConfig,
+    /// Whether the enclosing function returns a `Result`, gating the
+    /// `unwrap`/`expect` rewrite.
+    returns_result: bool,
+    /// Set while descending into a nested item definition, so `trace_vars` does
+    /// not instrument locals that belong to an inner `fn`/`impl` rather than the
+    /// annotated function body.
+    in_nested_item: bool,
+}
+
+/// Collect the leaf binding identifiers of a pattern, descending through
+/// tuple/struct/slice destructuring. Non-`Debug`-friendly composite patterns
+/// are handled by recording each leaf identifier rather than the whole pattern.
+fn collect_pat_idents(pat: &syn::Pat, out: &mut Vec<syn::Ident>) {
+    match pat {
+        syn::Pat::Ident(pat_ident) => out.push(pat_ident.ident.clone()),
+        syn::Pat::Reference(inner) => collect_pat_idents(&inner.pat, out),
+        syn::Pat::Type(inner) => collect_pat_idents(&inner.pat, out),
+        syn::Pat::Box(inner) => collect_pat_idents(&inner.pat, out),
+        syn::Pat::Tuple(tuple) => {
+            for elem in &tuple.elems {
+                collect_pat_idents(elem, out);
+            }
+        }
+        syn::Pat::TupleStruct(ts) => {
+            for elem in &ts.pat.elems {
+                collect_pat_idents(elem, out);
+            }
+        }
+        syn::Pat::Slice(slice) => {
+            for elem in &slice.elems {
+                collect_pat_idents(elem, out);
+            }
+        }
+        syn::Pat::Struct(st) => {
+            for field in &st.fields {
+                collect_pat_idents(&field.pat, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Build the recorder statement for a single bound identifier.
+///
+/// Emitting this call at all is gated on *this* crate's `trace_vars` feature
+/// (see [`VisitMut::visit_block_mut`]), which is paired with a `traceback-error`
+/// that provides `record_var`. The generated statement is therefore an
+/// unconditional call — no downstream `#[cfg]`, which would be evaluated in the
+/// consumer crate where the feature is not defined.
+fn record_var_stmt(ident: &syn::Ident) -> syn::Stmt {
+    let name = ident.to_string();
+    syn::parse2(quote_spanned! { ident.span()=>
+        ::traceback_error::record_var(#name, &#ident);
+    })
+    .expect("Failed to create record_var statement")
+}
+
+/// Build the history-mode error expression for the `?` rewrite, pushing a
+/// `{file, line, col, note}` frame via the companion crate's `push_frame` API.
+///
+/// The `push_frame`/`TracebackFrame` API only exists in `traceback-error` when
+/// this crate is compiled with the `history` feature, so the codegen is gated
+/// here (resolved in *this* crate, not the downstream consumer). Without the
+/// feature this returns `None` and the caller falls back to the ordinary wrap.
+#[allow(unused_variables)]
+fn history_build_err(
+    span: proc_macro2::Span,
+    context: &Option<String>,
+) -> Option<proc_macro2::TokenStream> {
+    #[cfg(feature = "history")]
+    {
+        let note = match context {
+            Some(ctx) => quote! { Some(#ctx.to_string()) },
+            None => quote! { None },
+        };
+        Some(quote_spanned! { span=>
+            ::traceback_error::push_frame(e, ::traceback_error::TracebackFrame {
+                file: file!(),
+                line: line!(),
+                col: column!(),
+                note: #note,
+            })
+        })
+    }
+    #[cfg(not(feature = "history"))]
+    {
+        None
+    }
+}
 
 impl VisitMut for TracingVisitor {
+    fn visit_item_mut(&mut self, item: &mut syn::Item) {
+        // Locals inside nested item definitions belong to an inner function, so
+        // `trace_vars` must not instrument them.
+        let prev = self.in_nested_item;
+        self.in_nested_item = true;
+        syn::visit_mut::visit_item_mut(self, item);
+        self.in_nested_item = prev;
+    }
+
+    fn visit_block_mut(&mut self, block: &mut syn::Block) {
+        // Apply the expression rewrites first, then inject the recorder
+        // statements for `trace_vars` after each binding/assignment.
+        syn::visit_mut::visit_block_mut(self, block);
+
+        // `traceback` already rejects `trace_vars` with a compile error unless
+        // the `trace_vars` feature is enabled, so reaching here with the flag set
+        // means `record_var` is available. The `cfg!` guard keeps the feature-off
+        // build of this file from emitting a call to the absent API.
+        if !self.config.trace_vars || self.in_nested_item || !cfg!(feature = "trace_vars") {
+            return;
+        }
+
+        let mut stmts = Vec::with_capacity(block.stmts.len());
+        for stmt in block.stmts.drain(..) {
+            let mut recorders = Vec::new();
+            match &stmt {
+                // Only instrument initialized bindings; `let x;` has nothing to
+                // record yet and taking `&x` would reference uninitialized memory.
+                syn::Stmt::Local(local) if local.init.is_some() => {
+                    let mut idents = Vec::new();
+                    collect_pat_idents(&local.pat, &mut idents);
+                    recorders.extend(idents.iter().map(record_var_stmt));
+                }
+                // `x = expr;` rebinds a simple identifier; record its new value.
+                syn::Stmt::Semi(Expr::Assign(assign), _) => {
+                    if let Expr::Path(path) = &*assign.left {
+                        if let Some(ident) = path.path.get_ident() {
+                            recorders.push(record_var_stmt(ident));
+                        }
+                    }
+                }
+                _ => {}
+            }
+            stmts.push(stmt);
+            stmts.append(&mut recorders);
+        }
+        block.stmts = stmts;
+    }
+
     fn visit_expr_mut(&mut self, expr: &mut Expr) {
         match expr {
-            Expr::Try(expr_try) => {
+            Expr::Try(expr_try) if self.config.try_ => {
                 let span = expr_try.question_token.span();
                 let inner_expr = &expr_try.expr;
+                let err_expr = match &self.config.context {
+                    Some(ctx) => quote! { traceback!(e, #ctx) },
+                    None => quote! { traceback!(err e) },
+                };
+                let log_event = match &self.config.log {
+                    Some(level) => quote! {
+                        ::tracing::event!(::tracing::Level::#level, error = ?e, "error propagated");
+                    },
+                    None => quote! {},
+                };
+                // In history mode, append a `{file, line, col, note}` frame to
+                // the error's ordered trace list (via the companion crate's
+                // `push_frame` API) rather than re-wrapping it opaquely. The
+                // `history` feature is known to be enabled here — `traceback`
+                // rejects the mode with a compile error otherwise — so the
+                // `unwrap_or` fallback only guards the feature-off build of this
+                // file and is never reached at a real expansion site.
+                let build_err = if self.config.history {
+                    history_build_err(span, &self.config.context).unwrap_or(err_expr)
+                } else {
+                    err_expr
+                };
                 let new_expr = syn::parse2(quote_spanned! { span=>{
                     match #inner_expr {
                         Ok(val) => Ok(val),
-                        Err(e) => Err(traceback!(err e))
+                        Err(e) => {
+                            #log_event
+                            Err(#build_err)
+                        }
                     }
                 }?
                 })
@@ -158,7 +479,116 @@ impl VisitMut for TracingVisitor {
 
                 *expr = new_expr;
             }
-            Expr::Index(index) => {
+            Expr::MethodCall(call)
+                if call.method == "unwrap" || call.method == "expect" =>
+            {
+                let span = call.method.span();
+                // `unwrap`/`expect` panic on the error case; without a `Result`
+                // return type we cannot turn that into an early `return Err(..)`.
+                if !self.returns_result {
+                    let message = format!(
+                        "`#[traceback]` can only rewrite `.{}()` in functions returning `Result`",
+                        call.method
+                    );
+                    *expr = syn::parse2(quote_spanned! { span=>
+                        compile_error!(#message)
+                    })
+                    .expect("Failed to create compile_error expression");
+                    return;
+                }
+
+                let receiver = &call.receiver;
+                // `.unwrap()`/`.expect()` exist on both `Option` and `Result`,
+                // and the macro cannot know which one the receiver is. Unify them
+                // through a local trait that collapses either into an `Option`, so
+                // the rewrite matches on `Some`/`None` and works uniformly. The
+                // note is the `expect` message (prefixed with the configured
+                // context, if any), or for `unwrap` the configured context / a
+                // default, rather than the inner error, which keeps the generated
+                // `traceback!` call to its `($msg:expr)` arm.
+                let err_expr = match call.args.first() {
+                    Some(msg) => match &self.config.context {
+                        Some(ctx) => quote! { traceback!(format!("{}: {}", #ctx, #msg)) },
+                        None => quote! { traceback!(#msg) },
+                    },
+                    None => match &self.config.context {
+                        Some(ctx) => quote! { traceback!(#ctx) },
+                        None => {
+                            let note = format!("called `.{}()` on an empty value", call.method);
+                            quote! { traceback!(#note) }
+                        }
+                    },
+                };
+
+                let new_expr = syn::parse2(quote_spanned! { span=> {
+                    trait __TbFallible {
+                        type Output;
+                        fn __tb_value(self) -> ::core::option::Option<Self::Output>;
+                    }
+                    impl<__T> __TbFallible for ::core::option::Option<__T> {
+                        type Output = __T;
+                        fn __tb_value(self) -> ::core::option::Option<__T> {
+                            self
+                        }
+                    }
+                    impl<__T, __E> __TbFallible for ::core::result::Result<__T, __E> {
+                        type Output = __T;
+                        fn __tb_value(self) -> ::core::option::Option<__T> {
+                            self.ok()
+                        }
+                    }
+                    match __TbFallible::__tb_value(#receiver) {
+                        ::core::option::Option::Some(val) => val,
+                        ::core::option::Option::None => return Err(#err_expr),
+                    }
+                } })
+                .expect("Failed to create traceback unwrap expression");
+
+                *expr = new_expr;
+            }
+            Expr::Macro(expr_macro) if expr_macro.mac.path.is_ident("panic") => {
+                let span = expr_macro.mac.path.span();
+                // Like `unwrap`/`expect`, turning a `panic!` into `return Err(..)`
+                // is only possible when the function returns a `Result`.
+                if !self.returns_result {
+                    let message =
+                        "`#[traceback]` can only rewrite `panic!` in functions returning `Result`";
+                    *expr = syn::parse2(quote_spanned! { span=>
+                        compile_error!(#message)
+                    })
+                    .expect("Failed to create compile_error expression");
+                    return;
+                }
+
+                // Reuse the `panic!` arguments as the traceback note via `format!`,
+                // matching the `($msg:expr)` arm; bare `panic!()` gets a default.
+                // The configured context, if any, is prefixed onto the note.
+                let tokens = &expr_macro.mac.tokens;
+                let message = if tokens.is_empty() {
+                    quote! { "explicit panic" }
+                } else {
+                    quote! { format!(#tokens) }
+                };
+                let message = match &self.config.context {
+                    Some(ctx) => quote! { format!("{}: {}", #ctx, #message) },
+                    None => message,
+                };
+                *expr = syn::parse2(quote_spanned! { span=>
+                    return Err(traceback!(#message))
+                })
+                .expect("Failed to create traceback panic expression");
+            }
+            Expr::Assign(assign) => {
+                // The left-hand side of an assignment is a place expression;
+                // rewriting `foo[i]` into `foo.get(i)` there would produce an
+                // assignment through an `Option`, so leave it untouched and only
+                // descend into the assigned value.
+                self.visit_expr_mut(&mut assign.right);
+            }
+            Expr::AssignOp(assign) => {
+                self.visit_expr_mut(&mut assign.right);
+            }
+            Expr::Index(index) if self.config.index => {
                 // Extract the parts of the index expression
                 let ExprIndex {
                     attrs: _,
@@ -167,15 +597,61 @@ impl VisitMut for TracingVisitor {
                     index,
                 } = index.clone();
 
-                // Create a new expression for safe indexing
-                let safe_indexing_expr = quote_spanned!(expr.span() =>
-                    match #inner_expr.get(#index) {
-                        Some(value) => value,
-                        None => {
-                            return Err(traceback!(format!("Error while indexing into {} in variable {:?}", #index, #inner_expr)));
-                        },
+                // Helper: prefix the configured context onto a format string.
+                let with_context = |body: proc_macro2::TokenStream| match &self.config.context {
+                    Some(ctx) => quote! { format!("{}: {}", #ctx, #body) },
+                    None => body,
+                };
+
+                let safe_indexing_expr = match &*index {
+                    // `arr[[1, 3, 5]]`: gather each index, returning on the first miss.
+                    Expr::Array(indices) => {
+                        let message = with_context(quote! {
+                            format!("Error while gathering index {} in variable {:?}", __tb_idx, #inner_expr)
+                        });
+                        quote_spanned!(expr.span() => {
+                            let mut __tb_gathered = Vec::new();
+                            for __tb_idx in #indices {
+                                match #inner_expr.get(__tb_idx) {
+                                    Some(value) => __tb_gathered.push(value),
+                                    None => {
+                                        return Err(traceback!(#message));
+                                    }
+                                }
+                            }
+                            __tb_gathered
+                        })
+                    }
+                    // Range indexing (`foo[a..b]`, `foo[..]`, `foo[a..=b]`) also
+                    // goes through `slice::get`, but reads as a slice rather than
+                    // an element, so distinguish it in the error message.
+                    Expr::Range(_) => {
+                        let message = with_context(quote! {
+                            format!("Error while slicing {:?} with range {}", #inner_expr, stringify!(#index))
+                        });
+                        quote_spanned!(expr.span() =>
+                            match #inner_expr.get(#index) {
+                                Some(value) => value,
+                                None => {
+                                    return Err(traceback!(#message));
+                                },
+                            }
+                        )
+                    }
+                    _ => {
+                        let message = with_context(quote! {
+                            format!("Error while indexing into {} in variable {:?}", #index, #inner_expr)
+                        });
+                        quote_spanned!(expr.span() =>
+                            match #inner_expr.get(#index) {
+                                Some(value) => value,
+                                None => {
+                                    return Err(traceback!(#message));
+                                },
+                            }
+                        )
                     }
-                );
+                };
 
                 // Replace the current expression with the safe indexing expression
                 *expr = syn::parse2(safe_indexing_expr).unwrap();